@@ -1,27 +1,158 @@
+#[macro_use]
+extern crate serde_derive;
 extern crate clap;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
 #[macro_use]
 extern crate failure;
 
+use std::path::Path;
+
+use clap::{App, Arg, SubCommand};
 use failure::Error;
-use clap::{App, SubCommand};
+
+mod diagnostics;
+mod manifest;
+mod treefile;
+use treefile::CheckPasswdType;
 
 fn hello() -> Result<(), Error> {
     println!("🦀");
     Ok(())
 }
 
+/// Structural problems found in a manifest by `validate`: a manifest that
+/// fails to parse, a missing required field, an empty list that rpm-ostree
+/// requires non-empty, a reference to an on-disk file that doesn't exist,
+/// or a `check-passwd`/`check-groups` of type `file` with no `filename`.
+fn collect_problems(manifest_path: &Path) -> Result<Vec<String>, Error> {
+    // `ref` is a required (non-`Option`) field, so a manifest missing it
+    // fails to deserialize at all rather than merely leaving the field
+    // empty. Surface that as a normal validation problem instead of
+    // aborting before any diagnostics are produced, so the caller still
+    // gets a `validate` record (and `--message-format json`/`json-pretty`
+    // isn't left with nothing to print).
+    let (manifest, dirs) = match manifest::load_manifest(manifest_path) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            let problem = if err.contains("missing field `ref`") {
+                "missing required `ref`".to_string()
+            } else {
+                format!("failed to parse manifest: {}", err)
+            };
+            return Ok(vec![problem]);
+        }
+    };
+    let mut problems = Vec::new();
+
+    if manifest.treeref.trim().is_empty() {
+        problems.push("missing required `ref`".to_string());
+    }
+    if manifest.repos.is_empty() {
+        problems.push("`repos` must not be empty".to_string());
+    }
+
+    // A file referenced by the leaf manifest may actually live in a parent
+    // manifest's directory, so check every directory in the include chain
+    // (same as `manifest_data_to_tmpdir` does when collecting these files).
+    let exists_in_chain = |name: &str| dirs.iter().any(|dir| dir.join(name).exists());
+    if let Some(ref script) = manifest.postprocess_script {
+        if !exists_in_chain(script) {
+            problems.push(format!("postprocess-script {:?} does not exist", script));
+        }
+    }
+    if let Some(ref add_files) = manifest.add_files {
+        for f in add_files {
+            if !exists_in_chain(f) {
+                problems.push(format!("add-files entry {:?} does not exist", f));
+            }
+        }
+    }
+    for (label, check) in &[
+        ("check-passwd", &manifest.check_passwd),
+        ("check-groups", &manifest.check_groups),
+    ] {
+        if let Some(check) = check {
+            if let CheckPasswdType::File = check.variant {
+                if check.filename.is_none() {
+                    problems.push(format!("{} has type `file` but no `filename`", label));
+                }
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+fn validate(manifest_path: &Path, message_format: &str) -> Result<(), Error> {
+    let problems = collect_problems(manifest_path)?;
+
+    if message_format == "human" {
+        if problems.is_empty() {
+            println!("{}: OK", manifest_path.display());
+        } else {
+            println!(
+                "{}: {} problem(s):",
+                manifest_path.display(),
+                problems.len()
+            );
+            for problem in &problems {
+                println!("  - {}", problem);
+            }
+        }
+    } else {
+        diagnostics::print_record(
+            message_format,
+            &::serde_json::json!({
+                "type": "validate",
+                "manifest": manifest_path.to_string_lossy(),
+                "problems": problems,
+            }),
+        );
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        bail!(
+            "{}: {} validation problem(s)",
+            manifest_path.display(),
+            problems.len()
+        )
+    }
+}
+
 fn run() -> Result<(), Error> {
     let matches = App::new("coreos-assembler")
         .version("0.1")
         .about("CoreOS assembler")
+        .subcommand(SubCommand::with_name("hello").about("Say hello"))
         .subcommand(
-            SubCommand::with_name("hello")
-                .about("Say hello")
+            SubCommand::with_name("validate")
+                .about("Type-check a manifest without invoking rpm-ostree")
+                .arg(
+                    Arg::with_name("message-format")
+                        .long("message-format")
+                        .takes_value(true)
+                        .possible_values(diagnostics::MESSAGE_FORMAT_VALUES)
+                        .default_value("human")
+                        .help("Output format for diagnostic records"),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .required(true)
+                        .help("Path to a YAML or JSON treefile"),
+                ),
         )
         .get_matches();
 
     match matches.subcommand() {
         ("hello", _) => hello(),
+        ("validate", Some(sub_m)) => validate(
+            Path::new(sub_m.value_of("manifest").unwrap()),
+            sub_m.value_of("message-format").unwrap(),
+        ),
         ("", _) => bail!("No command given"),
         _ => unreachable!(),
     }
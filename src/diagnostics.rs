@@ -0,0 +1,15 @@
+// Shared `--message-format` support: a `human` mode that a caller renders
+// itself with ad-hoc text, and `json`/`json-pretty` modes that print one
+// structured record per call so wrapping automation can consume it.
+
+pub const MESSAGE_FORMAT_VALUES: &[&str] = &["human", "json", "json-pretty"];
+
+/// Print a structured record in `json` or `json-pretty` mode; no-op in
+/// `human` mode, which prints its own ad-hoc text inline instead.
+pub fn print_record(message_format: &str, value: &::serde_json::Value) {
+    match message_format {
+        "json" => println!("{}", value),
+        "json-pretty" => println!("{}", ::serde_json::to_string_pretty(value).unwrap()),
+        _ => {}
+    }
+}
@@ -0,0 +1,135 @@
+// Support for recording and validating the exact package set resolved for
+// a compose, analogous to how `Cargo.lock` pins a dependency graph so a
+// build can be reproduced and audited later.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub release: String,
+    pub repo: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PackageLock {
+    pub packages: Vec<LockedPackage>,
+}
+
+impl PackageLock {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let f = fs::File::open(path)?;
+        serde_json::from_reader(f).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let f = fs::File::create(path)?;
+        serde_json::to_writer_pretty(f, self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn by_name(&self) -> BTreeMap<&str, &LockedPackage> {
+        self.packages
+            .iter()
+            .map(|pkg| (pkg.name.as_str(), pkg))
+            .collect()
+    }
+}
+
+/// A single deviation between a `treefile.lock.json` and what was actually
+/// resolved on a subsequent compose.
+#[derive(Serialize, Debug)]
+pub enum PackageDiff {
+    Added(LockedPackage),
+    Removed(LockedPackage),
+    Changed {
+        name: String,
+        from: LockedPackage,
+        to: LockedPackage,
+    },
+}
+
+/// Compare a lockfile against a freshly resolved package set.
+pub fn diff(locked: &PackageLock, resolved: &PackageLock) -> Vec<PackageDiff> {
+    let locked_by_name = locked.by_name();
+    let resolved_by_name = resolved.by_name();
+    let mut diffs = Vec::new();
+    for (name, pkg) in &resolved_by_name {
+        match locked_by_name.get(name) {
+            None => diffs.push(PackageDiff::Added((*pkg).clone())),
+            Some(old) if *old != *pkg => diffs.push(PackageDiff::Changed {
+                name: (*name).to_string(),
+                from: (*old).clone(),
+                to: (*pkg).clone(),
+            }),
+            _ => {}
+        }
+    }
+    for (name, pkg) in &locked_by_name {
+        if !resolved_by_name.contains_key(name) {
+            diffs.push(PackageDiff::Removed((*pkg).clone()));
+        }
+    }
+    diffs
+}
+
+/// Query `rpm-ostree` for the NEVRAs actually resolved into `commit`.
+/// `repo`, when given, targets the same repo the compose wrote to rather
+/// than whatever `rpm-ostree`'s default/system repo happens to be.
+pub fn query_resolved_packages(commit: &str, repo: Option<&str>) -> Result<PackageLock, String> {
+    let mut cmd = Command::new("rpm-ostree");
+    cmd.args(&["db", "list"]);
+    if let Some(repo) = repo {
+        cmd.arg("--repo").arg(repo);
+    }
+    cmd.arg(commit);
+    let output = cmd.output().map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!("rpm-ostree db list failed: {}", output.status));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let packages = stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(parse_nevra_line)
+        .collect();
+    Ok(PackageLock { packages })
+}
+
+/// Parse a `rpm-ostree db list` line of the form
+/// `name-version-release.arch (repo)` into its name, version, release and
+/// repo; the arch is dropped, and an epoch stays part of the version.
+fn parse_nevra_line(line: &str) -> Option<LockedPackage> {
+    let mut fields = line.splitn(2, char::is_whitespace);
+    let nevra = fields.next()?;
+    let repo = fields
+        .next()
+        .unwrap_or("")
+        .trim()
+        .trim_matches(|c| c == '(' || c == ')')
+        .to_string();
+
+    let dot = nevra.rfind('.')?;
+    let name_version_release = &nevra[..dot];
+
+    let release_dash = name_version_release.rfind('-')?;
+    let name_version = &name_version_release[..release_dash];
+    let release = &name_version_release[release_dash + 1..];
+
+    let version_dash = name_version.rfind('-')?;
+    let name = &name_version[..version_dash];
+    let version = &name_version[version_dash + 1..];
+
+    Some(LockedPackage {
+        name: name.to_string(),
+        version: version.to_string(),
+        release: release.to_string(),
+        repo,
+    })
+}
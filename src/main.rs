@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate serde_derive;
+extern crate clap;
 extern crate serde;
 extern crate serde_json;
 extern crate serde_yaml;
@@ -7,26 +8,21 @@ extern crate tempfile;
 
 use std::borrow::Cow;
 use std::ops::Deref;
-use std::path::Path;
-use std::{env, fs, io, mem, process};
+use std::path::{Path, PathBuf};
+use std::{fs, io, mem, process};
 
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+mod diagnostics;
+mod lockfile;
+mod manifest;
 mod treefile;
+use diagnostics::print_record;
+use manifest::is_yaml;
 use treefile::TreeComposeConfig;
 
-// For convenience we allow the list to have multiple packages
-// per item (intended for YAML).
-fn whitespace_split_packages(pkgs: &Vec<String>) -> Vec<String> {
-    let mut ret = Vec::with_capacity(pkgs.len());
-    for pkg in pkgs {
-        for pkg_item in pkg.split_whitespace() {
-            ret.push(pkg_item.into());
-        }
-    }
-    return ret;
-}
-
 fn manifest_data_to_tmpdir(
-    path: &Path,
+    dirs: &[PathBuf],
     manifest: &TreeComposeConfig,
 ) -> io::Result<tempfile::TempDir> {
     let tmpdir = tempfile::tempdir_in("/tmp")?;
@@ -34,37 +30,146 @@ fn manifest_data_to_tmpdir(
         .postprocess_script
         .as_ref()
         .map_or("", String::as_str);
-    // Handle unprefixed path
-    let path = if path.as_os_str().is_empty() {
-        Path::new(".")
-    } else {
-        path
-    };
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
+    // Files explicitly referenced by the manifest that aren't covered by
+    // the hardcoded extension/name list below (e.g. an `add-files` entry
+    // with no recognized extension, or a `check-passwd`/`check-groups`
+    // `filename` pointing at an arbitrary data file).
+    let mut extra_files: Vec<&str> = Vec::new();
+    if let Some(ref add_files) = manifest.add_files {
+        extra_files.extend(add_files.iter().map(String::as_str));
+    }
+    for check in &[&manifest.check_passwd, &manifest.check_groups] {
+        if let Some(check) = check {
+            if let Some(ref filename) = check.filename {
+                extra_files.push(filename);
+            }
         }
-        // Hardcoded list of external files
-        let bn = entry.file_name();
-        let bn = bn.to_str().unwrap();
-        if bn.ends_with(".repo") || bn.ends_with(".json") || bn == "passwd" || bn == "group"
-            || bn == postprocess_script
-        {
-            fs::copy(path, tmpdir.path().join(bn))?;
+    }
+    for dir in dirs {
+        // Handle unprefixed path
+        let dir: &Path = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            // Hardcoded list of external files
+            let bn = entry.file_name();
+            let bn = bn.to_str().unwrap();
+            if bn.ends_with(".repo")
+                || bn.ends_with(".json")
+                || bn == "passwd"
+                || bn == "group"
+                || bn == postprocess_script
+                || extra_files.contains(&bn)
+            {
+                // Later directories (closer to the leaf manifest in an
+                // include chain) win if the same filename appears twice.
+                fs::copy(path, tmpdir.path().join(bn))?;
+            }
         }
     }
     return Ok(tmpdir);
 }
 
-fn is_yaml(name: &str) -> bool {
-    name.ends_with(".yaml")
+/// Build the clap `App`. Override flags are top-level options given before
+/// the manifest path: with `TrailingVarArg`, everything from the first
+/// positional argument on is taken as the manifest path plus arguments
+/// passed through to `rpm-ostree`.
+fn app() -> App<'static, 'static> {
+    App::new("rpm-ostree-compose-tree-wrapper")
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("override-ref")
+                .long("override-ref")
+                .takes_value(true)
+                .global(true)
+                .help("Override the manifest's ref"),
+        )
+        .arg(
+            Arg::with_name("override-releasever")
+                .long("override-releasever")
+                .takes_value(true)
+                .global(true)
+                .help("Override the manifest's releasever"),
+        )
+        .arg(
+            Arg::with_name("add-repo")
+                .long("add-repo")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true)
+                .global(true)
+                .help("Append a repo to the manifest (repeatable)"),
+        )
+        .arg(
+            Arg::with_name("add-package")
+                .long("add-package")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true)
+                .global(true)
+                .help("Append a package to the manifest (repeatable)"),
+        )
+        .arg(
+            Arg::with_name("frozen")
+                .long("frozen")
+                .visible_alias("locked")
+                .global(true)
+                .help("Fail if resolved package versions drift from treefile.lock.json"),
+        )
+        .arg(
+            Arg::with_name("message-format")
+                .long("message-format")
+                .takes_value(true)
+                .possible_values(diagnostics::MESSAGE_FORMAT_VALUES)
+                .default_value("human")
+                .global(true)
+                .help("Output format for diagnostic records"),
+        )
+        .arg(
+            Arg::with_name("args")
+                .multiple(true)
+                .help("Manifest path, plus any arguments passed through to rpm-ostree"),
+        )
+}
+
+/// Patch the in-memory manifest with whatever `--override-*`/`--add-*`
+/// flags were given on the command line.
+fn apply_overrides(manifest: &mut TreeComposeConfig, matches: &ArgMatches) {
+    if let Some(treeref) = matches.value_of("override-ref") {
+        manifest.treeref = treeref.to_string();
+    }
+    if let Some(releasever) = matches.value_of("override-releasever") {
+        manifest.releasever = Some(releasever.to_string());
+    }
+    if let Some(repos) = matches.values_of("add-repo") {
+        manifest.repos.extend(repos.map(String::from));
+    }
+    if let Some(packages) = matches.values_of("add-package") {
+        manifest.packages.extend(packages.map(String::from));
+    }
+}
+
+fn has_overrides(matches: &ArgMatches) -> bool {
+    matches.is_present("override-ref")
+        || matches.is_present("override-releasever")
+        || matches.is_present("add-repo")
+        || matches.is_present("add-package")
 }
 
 fn run() -> Result<(), String> {
+    let matches = app().get_matches();
+    let message_format = matches.value_of("message-format").unwrap();
     let mut manifest_index: Option<usize> = None;
-    let env_args: Vec<String> = env::args().skip(1).collect();
+    let env_args: Vec<String> = matches
+        .values_of("args")
+        .map_or_else(Vec::new, |v| v.map(String::from).collect());
     // Replace our argv with rpm-ostree compose tree [original argv]
     let base_args = &["compose", "tree"];
     let mut args: Vec<Cow<str>> = base_args
@@ -84,54 +189,138 @@ fn run() -> Result<(), String> {
     let manifest_index = manifest_index.unwrap();
     let manifest_path = (args[manifest_index]).to_string();
     let manifest_path = Path::new(&manifest_path);
-    let manifest_f = fs::File::open(manifest_path).map_err(|err| err.to_string())?;
+    let is_yaml_manifest = is_yaml(manifest_path.to_str().unwrap());
+
+    // We always parse the manifest (cheap) so its `ref` is available for
+    // lockfile generation/checking below, regardless of invocation shape.
+    // NOTE: this narrows what we accept versus the pre-lockfile baseline,
+    // where a JSON manifest with no overrides/`--frozen` was handed to
+    // rpm-ostree untouched and never parsed here at all. Such a manifest
+    // must now also deserialize cleanly into the full `TreeComposeConfig`
+    // purely so lockfile bookkeeping has `treeref`, even though it's never
+    // re-serialized in that path.
+    let (mut manifest, dirs) = manifest::load_manifest(manifest_path)?;
+    apply_overrides(&mut manifest, &matches);
+    let treeref = manifest.treeref.clone();
 
     // In the YAML case, we generate JSON from it in a temporary directory,
-    // copying in the other files that are referenced by the manifest.
+    // copying in the other files that are referenced by the manifest. We
+    // also take this path for JSON manifests if any overrides were given,
+    // since those need to be patched in before handing off to rpm-ostree.
     let mut tmpd: Option<tempfile::TempDir> = None;
-    if is_yaml(manifest_path.to_str().unwrap()) {
-        let mut manifest: TreeComposeConfig =
-            serde_yaml::from_reader(manifest_f).map_err(|err| err.to_string())?;
-        if manifest.include.is_some() {
-            return Err("include: is currently not supported in YAML syntax".into());
+    if is_yaml_manifest || has_overrides(&matches) {
+        if message_format == "human" {
+            println!("Parsed manifest:");
+            println!("  {:?}", manifest);
         }
-        let new_pkgs = whitespace_split_packages(&manifest.packages);
-        manifest.packages = new_pkgs;
-        println!("Parsed manifest:");
-        println!("  {:?}", manifest);
 
-        tmpd = Some(
-            manifest_data_to_tmpdir(manifest_path.parent().unwrap(), &manifest)
-                .map_err(|err| err.to_string())?,
-        );
+        tmpd = Some(manifest_data_to_tmpdir(&dirs, &manifest).map_err(|err| err.to_string())?);
         let tmpd_v = tmpd.as_ref().unwrap();
         let tmpd_path = tmpd_v.path();
-        println!("Converting to JSON, tmpdir={:?}", tmpd_path);
+        if message_format == "human" {
+            println!("Converting to JSON, tmpdir={:?}", tmpd_path);
+        } else {
+            print_record(
+                message_format,
+                &serde_json::json!({
+                    "type": "manifest",
+                    "ref": manifest.treeref,
+                    "packages": manifest.packages,
+                    "tmpdir": tmpd_path.to_string_lossy(),
+                }),
+            );
+        }
         let bfn = manifest_path.file_name().unwrap();
         let bn = bfn.to_str().unwrap().replace(".yaml", ".json");
         let manifest_json_path = tmpd_path.join(bn);
         let out_json = fs::File::create(&manifest_json_path).map_err(|err| err.to_string())?;
         serde_json::to_writer_pretty(out_json, &manifest).map_err(|err| err.to_string())?;
 
-        // Replace the YAML argument with JSON
+        // Replace the original manifest argument with the effective one
         let manifest_path_str = manifest_json_path.to_str().unwrap();
         args[manifest_index] = Cow::Owned(manifest_path_str.to_string());
     }
     // libc::execve() is unsafe sadly, and also we want to clean up the tmpdir.
     // But we basically pass through all arguments other than the manifest
     // unchanged.
-    println!("Executing: rpm-ostree {:?}", args);
+    if message_format == "human" {
+        println!("Executing: rpm-ostree {:?}", args);
+    } else {
+        print_record(
+            message_format,
+            &serde_json::json!({
+                "type": "exec",
+                "program": "rpm-ostree",
+                "args": args.iter().map(|a| a.as_ref()).collect::<Vec<&str>>(),
+            }),
+        );
+    }
     let status = process::Command::new("rpm-ostree")
         .args(args.iter().map(|v| v.deref()))
         .stdin(process::Stdio::null())
         .status()
         .map_err(|err| err.to_string())?;
     mem::forget(tmpd);
-    if status.success() {
-        Ok(())
+    if !status.success() {
+        return Err(format!("rpm-ostree compose tree failed: {}", status));
+    }
+
+    let lock_path = manifest_path
+        .parent()
+        .map_or_else(|| PathBuf::from("."), |p| p.to_path_buf())
+        .join("treefile.lock.json");
+    // Query the same repo rpm-ostree just composed into, not whatever
+    // the default repo happens to be.
+    let repo = find_repo_arg(&args);
+    let resolved = lockfile::query_resolved_packages(&treeref, repo.as_ref().map(String::as_str))?;
+    if matches.is_present("frozen") {
+        let locked = lockfile::PackageLock::load(&lock_path)
+            .map_err(|err| format!("reading {}: {}", lock_path.display(), err))?;
+        let diffs = lockfile::diff(&locked, &resolved);
+        if !diffs.is_empty() {
+            if message_format == "human" {
+                for d in &diffs {
+                    println!("{:?}", d);
+                }
+            } else {
+                print_record(
+                    message_format,
+                    &serde_json::json!({
+                        "type": "drift",
+                        "lockfile": lock_path.to_string_lossy(),
+                        "diffs": diffs,
+                    }),
+                );
+            }
+            return Err(format!(
+                "resolved packages drifted from {}",
+                lock_path.display()
+            ));
+        }
     } else {
-        Err(format!("rpm-ostree compose tree failed: {}", status))
+        resolved
+            .write(&lock_path)
+            .map_err(|err| format!("writing {}: {}", lock_path.display(), err))?;
+    }
+    Ok(())
+}
+
+/// Find the `--repo`/`--repo=<path>` value among the pass-through
+/// `rpm-ostree` arguments, so lockfile queries target the same repo the
+/// preceding compose actually wrote to.
+fn find_repo_arg(args: &[Cow<str>]) -> Option<String> {
+    let prefix = "--repo=";
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let arg: &str = arg.as_ref();
+        if arg.starts_with(prefix) {
+            return Some(arg[prefix.len()..].to_string());
+        }
+        if arg == "--repo" {
+            return iter.next().map(|v| v.as_ref().to_string());
+        }
     }
+    None
 }
 
 fn main() {
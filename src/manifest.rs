@@ -0,0 +1,73 @@
+// Shared YAML/JSON treefile loading, used by both the rpm-ostree compose
+// wrapper and the `coreos-assembler validate` subcommand so the two stay
+// in lockstep on what counts as a valid manifest.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use treefile::{Merge, TreeComposeConfig};
+
+pub fn is_yaml(name: &str) -> bool {
+    name.ends_with(".yaml")
+}
+
+// For convenience we allow the list to have multiple packages
+// per item (intended for YAML).
+pub fn whitespace_split_packages(pkgs: &Vec<String>) -> Vec<String> {
+    let mut ret = Vec::with_capacity(pkgs.len());
+    for pkg in pkgs {
+        for pkg_item in pkg.split_whitespace() {
+            ret.push(pkg_item.into());
+        }
+    }
+    return ret;
+}
+
+/// Load a YAML manifest, following `include:` directives recursively.
+/// Returns the fully merged config along with every directory in the
+/// chain (parent-first) so external files can be collected from each.
+pub fn load_manifest_chain(
+    path: &Path,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<(TreeComposeConfig, Vec<PathBuf>), String> {
+    let abspath = fs::canonicalize(path).map_err(|err| err.to_string())?;
+    if !visited.insert(abspath.clone()) {
+        return Err(format!("include cycle detected at {}", abspath.display()));
+    }
+    let f = fs::File::open(path).map_err(|err| err.to_string())?;
+    let manifest: TreeComposeConfig =
+        ::serde_yaml::from_reader(f).map_err(|err| err.to_string())?;
+    let dir = path
+        .parent()
+        .map_or_else(|| PathBuf::from("."), |p| p.to_path_buf());
+    let include = manifest.include.clone();
+    if let Some(include) = include {
+        let include_path = dir.join(include);
+        let (parent, mut dirs) = load_manifest_chain(&include_path, visited)?;
+        dirs.push(dir);
+        Ok((manifest.merge(parent), dirs))
+    } else {
+        Ok((manifest, vec![dir]))
+    }
+}
+
+/// Load a manifest (YAML, following includes, or plain JSON) and apply the
+/// whitespace-split-packages normalization. Returns the config plus every
+/// directory that may contain files the manifest references.
+pub fn load_manifest(path: &Path) -> Result<(TreeComposeConfig, Vec<PathBuf>), String> {
+    let (mut manifest, dirs) = if is_yaml(path.to_str().unwrap()) {
+        let mut visited = HashSet::new();
+        load_manifest_chain(path, &mut visited)?
+    } else {
+        let f = fs::File::open(path).map_err(|err| err.to_string())?;
+        let manifest: TreeComposeConfig =
+            ::serde_json::from_reader(f).map_err(|err| err.to_string())?;
+        let dir = path
+            .parent()
+            .map_or_else(|| PathBuf::from("."), |p| p.to_path_buf());
+        (manifest, vec![dir])
+    };
+    manifest.packages = whitespace_split_packages(&manifest.packages);
+    Ok((manifest, dirs))
+}
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum BootLocation {
     #[serde(rename = "both")]
@@ -29,19 +31,98 @@ pub enum CheckPasswdType {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CheckPasswd {
     #[serde(rename = "type")]
-    variant: CheckPasswdType,
-    filename: Option<String>,
+    pub variant: CheckPasswdType,
+    pub filename: Option<String>,
     // Skip this for now, a separate file is easier
     // and anyways we want to switch to sysusers
     // entries: Option<Map<>String>,
 }
 
+/// Merge a child config with its include parent. Scalar `Option<T>` fields
+/// are overridden by the child if `Some`; the few `Vec<String>` fields that
+/// accumulate across a manifest chain (packages, repos, units, remove_files)
+/// are concatenated instead, with the parent's entries first.
+pub trait Merge {
+    fn merge(self, parent: Self) -> Self;
+}
+
+fn merge_opt<T>(child: Option<T>, parent: Option<T>) -> Option<T> {
+    child.or(parent)
+}
+
+fn concat_vec(parent: Vec<String>, child: Vec<String>) -> Vec<String> {
+    let mut ret = parent;
+    ret.extend(child);
+    ret
+}
+
+fn concat_opt_vec(parent: Option<Vec<String>>, child: Option<Vec<String>>) -> Option<Vec<String>> {
+    match (parent, child) {
+        (Some(p), Some(c)) => Some(concat_vec(p, c)),
+        (Some(p), None) => Some(p),
+        (None, Some(c)) => Some(c),
+        (None, None) => None,
+    }
+}
+
+impl Merge for TreeComposeConfig {
+    fn merge(self, parent: Self) -> Self {
+        let child = self;
+        TreeComposeConfig {
+            treeref: child.treeref,
+            repos: concat_vec(parent.repos, child.repos),
+            selinux: merge_opt(child.selinux, parent.selinux),
+            gpg_key: merge_opt(child.gpg_key, parent.gpg_key),
+            include: None,
+            packages: concat_vec(parent.packages, child.packages),
+            bootstrap_packages: merge_opt(child.bootstrap_packages, parent.bootstrap_packages),
+            documentation: merge_opt(child.documentation, parent.documentation),
+            install_langs: merge_opt(child.install_langs, parent.install_langs),
+            initramfs_args: merge_opt(child.initramfs_args, parent.initramfs_args),
+            boot_location: merge_opt(child.boot_location, parent.boot_location),
+            tmp_is_dir: merge_opt(child.tmp_is_dir, parent.tmp_is_dir),
+            units: concat_opt_vec(parent.units, child.units),
+            default_target: merge_opt(child.default_target, parent.default_target),
+            releasever: merge_opt(child.releasever, parent.releasever),
+            automatic_version_prefix: merge_opt(
+                child.automatic_version_prefix,
+                parent.automatic_version_prefix,
+            ),
+            mutate_os_release: merge_opt(child.mutate_os_release, parent.mutate_os_release),
+            etc_group_members: merge_opt(child.etc_group_members, parent.etc_group_members),
+            preserve_passwd: merge_opt(child.preserve_passwd, parent.preserve_passwd),
+            check_passwd: merge_opt(child.check_passwd, parent.check_passwd),
+            check_groups: merge_opt(child.check_groups, parent.check_groups),
+            ignore_removed_users: merge_opt(
+                child.ignore_removed_users,
+                parent.ignore_removed_users,
+            ),
+            ignore_removed_groups: merge_opt(
+                child.ignore_removed_groups,
+                parent.ignore_removed_groups,
+            ),
+            postprocess_script: merge_opt(child.postprocess_script, parent.postprocess_script),
+            add_files: merge_opt(child.add_files, parent.add_files),
+            remove_files: concat_opt_vec(parent.remove_files, child.remove_files),
+            remove_from_packages: merge_opt(
+                child.remove_from_packages,
+                parent.remove_from_packages,
+            ),
+            extra: {
+                let mut extra = parent.extra;
+                extra.extend(child.extra);
+                extra
+            },
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TreeComposeConfig {
     // Compose controls
     #[serde(rename = "ref")]
     pub treeref: String,
-    repos: Vec<String>,
+    pub repos: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub selinux: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -63,11 +144,15 @@ pub struct TreeComposeConfig {
     #[serde(rename = "initramfs-args")]
     pub initramfs_args: Option<Vec<String>>,
 
-    // Tree layout options
-    #[serde(default)]
-    pub boot_location: BootLocation,
-    #[serde(default)]
-    pub tmp_is_dir: bool,
+    // Tree layout options. `None` here means "unspecified at every level of
+    // the include chain", in which case rpm-ostree applies the same
+    // defaults as `BootLocation::default()`/`false` itself; tracking
+    // presence as `Option` (rather than defaulting eagerly) lets a leaf
+    // manifest omit these without clobbering a parent's explicit setting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_location: Option<BootLocation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmp_is_dir: Option<bool>,
 
     // systemd
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -115,4 +200,9 @@ pub struct TreeComposeConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "remove-from-packages")]
     pub remove_from_packages: Option<Vec<Vec<String>>>,
+
+    // Catch-all for rpm-ostree treefile keys we don't model yet, so they
+    // round-trip into the generated JSON instead of being silently dropped.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, ::serde_json::Value>,
 }